@@ -36,7 +36,7 @@
 //!     assert_eq!(false, feature::is_enabled(feature::Alpha));
 //!     assert_eq!(false, feature::is_enabled(feature::Beta));
 //!
-//!     feature::enable(feature::Beta);
+//!     feature::enable(feature::Beta).unwrap();
 //!     assert_eq!(false, feature::is_enabled(feature::Alpha));
 //!     assert_eq!(true, feature::is_enabled(feature::Beta));
 //! }
@@ -66,8 +66,8 @@
 //!
 //! fn main() {
 //!     // Parse CLI args, environment, read config file etc...
-//!     srv::enable(srv::BitTorrentDownloading);
-//!     ux::enable(ux::JsonOutput);
+//!     srv::enable(srv::BitTorrentDownloading).unwrap();
+//!     ux::enable(ux::JsonOutput).unwrap();
 //!
 //!     if srv::is_enabled(srv::Http2Downloading) {
 //!         println!("Downloading via http2...");
@@ -127,7 +127,7 @@ extern crate bitflags;
 ///     assert_eq!(false, feature::is_enabled(feature::Alpha));
 ///     assert_eq!(false, feature::is_enabled(feature::Beta));
 ///
-///     feature::enable(feature::Beta);
+///     feature::enable(feature::Beta).unwrap();
 ///     assert_eq!(false, feature::is_enabled(feature::Alpha));
 ///     assert_eq!(true, feature::is_enabled(feature::Beta));
 /// }
@@ -157,8 +157,8 @@ extern crate bitflags;
 ///
 /// fn main() {
 ///     // Parse CLI args, environment, read config file etc...
-///     srv::enable(srv::BitTorrentDownloading);
-///     ux::enable(ux::JsonOutput);
+///     srv::enable(srv::BitTorrentDownloading).unwrap();
+///     ux::enable(ux::JsonOutput).unwrap();
 ///
 ///     if srv::is_enabled(srv::Http2Downloading) {
 ///         println!("Downloading via http2...");
@@ -176,57 +176,310 @@ extern crate bitflags;
 ///
 #[macro_export]
 macro_rules! features {
+    // The no-`requires` forms keep the baseline's full `:expr` value fragment so that
+    // multi-token values such as `1 << 3` keep compiling. The `requires` forms below
+    // must use `:tt` (an `:expr` fragment may not be followed by the `requires` ident),
+    // so in a block where any flag declares a `requires` clause, every flag's value is
+    // limited to a single token.
     (mod $mod_name:ident {
-        $($(#[$flag_attr:meta])* const $flag:ident = $value:expr),+
+        $($(#$flag_attr:tt)* const $flag:ident = $value:expr),+
     }) => {
         #[allow(non_upper_case_globals)]
         mod $mod_name {
             features! {
                 @_impl mod $mod_name {
-                    $($(#[$flag_attr])* const $flag = $value),+
+                    $($(#$flag_attr)* const $flag = $value),+
                 }
             }
         }
     };
     (pub mod $mod_name:ident {
-        $($(#[$flag_attr:meta])* const $flag:ident = $value:expr),+
+        $($(#$flag_attr:tt)* const $flag:ident = $value:expr),+
     }) => {
         #[allow(non_upper_case_globals)]
         pub mod $mod_name {
             features! {
                 @_impl mod $mod_name {
-                    $($(#[$flag_attr])* const $flag = $value),+
+                    $($(#$flag_attr)* const $flag = $value),+
+                }
+            }
+        }
+    };
+    (mod $mod_name:ident {
+        $($(#$flag_attr:tt)* const $flag:ident = $value:tt $(requires [$($req:ident),*])*),+
+    }) => {
+        #[allow(non_upper_case_globals)]
+        mod $mod_name {
+            features! {
+                @_impl mod $mod_name {
+                    $($(#$flag_attr)* const $flag = $value $(requires [$($req),*])*),+
+                }
+            }
+        }
+    };
+    (pub mod $mod_name:ident {
+        $($(#$flag_attr:tt)* const $flag:ident = $value:tt $(requires [$($req:ident),*])*),+
+    }) => {
+        #[allow(non_upper_case_globals)]
+        pub mod $mod_name {
+            features! {
+                @_impl mod $mod_name {
+                    $($(#$flag_attr)* const $flag = $value $(requires [$($req),*])*),+
                 }
             }
         }
     };
     (@_impl mod $mod_name:ident {
-        $($(#[$flag_attr:meta])* const $flag:ident = $value:expr),+
+        $($(#$flag_attr:tt)* const $flag:ident = $value:tt $(requires [$($req:ident),*])*),+
     }) => {
         use std::sync::atomic;
 
         bitflags! {
             pub flags Flags: usize {
-                $($(#[$flag_attr])* const $flag = $value),+
+                $(const $flag = $value),+
+            }
+        }
+
+        static FEATURES: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+        static NAMES: &'static [(&'static str, Flags)] = &[
+            $((stringify!($flag), $flag)),+
+        ];
+
+        static STABILITY: &'static [(Flags, Stability)] = &[
+            $(($flag, features!(@_stability $(#$flag_attr)*))),+
+        ];
+
+        static REQUIRES: &'static [(Flags, &'static [Flags])] = &[
+            $(($flag, &[$($($req),*)*])),+
+        ];
+
+        static ALLOW_UNSTABLE: atomic::AtomicBool = atomic::AtomicBool::new(false);
+        static DISABLE_DEPENDENTS: atomic::AtomicBool = atomic::AtomicBool::new(false);
+        static DEPRECATION_WARNED: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+        // Expands the given flags to include everything they transitively require, OR-ing in
+        // required flags until the bitset stops changing. Monotonic, so it terminates even if
+        // the `requires` clauses describe a cycle.
+        fn requirements_closure(flag: Flags) -> Flags {
+            let mut closure = flag;
+            loop {
+                let mut next = closure;
+                for &(f, reqs) in REQUIRES {
+                    if closure.contains(f) {
+                        for &req in reqs {
+                            next.insert(req);
+                        }
+                    }
+                }
+                if next == closure {
+                    break;
+                }
+                closure = next;
+            }
+            closure
+        }
+
+        #[allow(dead_code)]
+        pub fn requirements_of(flag: Flags) -> Flags {
+            let mut reqs = requirements_closure(flag);
+            reqs.remove(flag);
+            reqs
+        }
+
+        #[allow(dead_code)]
+        pub fn disable_dependents(cascade: bool) {
+            DISABLE_DEPENDENTS.store(cascade, atomic::Ordering::SeqCst);
+        }
+
+        /// The lifecycle classification of a feature flag.
+        #[allow(dead_code)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Stability {
+            Stable,
+            Unstable { since: &'static str },
+            Deprecated { since: &'static str },
+        }
+
+        /// Returned when a flag cannot be enabled because its stability gate is closed.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct FeatureState {
+            pub flag: Flags,
+            pub stability: Stability,
+        }
+
+        impl ::std::fmt::Display for FeatureState {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                write!(f, "feature is not enabled in this configuration: {:?}", self.stability)
+            }
+        }
+
+        impl ::std::error::Error for FeatureState {
+            fn description(&self) -> &str {
+                "feature stability gate is closed"
+            }
+        }
+
+        #[allow(dead_code)]
+        pub fn allow_unstable(allow: bool) {
+            ALLOW_UNSTABLE.store(allow, atomic::Ordering::SeqCst);
+        }
+
+        fn unstable_allowed() -> bool {
+            ALLOW_UNSTABLE.load(atomic::Ordering::SeqCst)
+                || ::std::env::var("FEATURES_ALLOW_UNSTABLE").is_ok()
+        }
+
+        #[allow(dead_code)]
+        pub fn state_of(flag: Flags) -> Stability {
+            STABILITY.iter()
+                .find(|&&(f, _)| f == flag)
+                .map(|&(_, stability)| stability)
+                .unwrap_or(Stability::Stable)
+        }
+
+        #[allow(dead_code)]
+        pub fn all() -> &'static [(&'static str, Flags)] {
+            NAMES
+        }
+
+        #[allow(dead_code)]
+        pub fn from_name(name: &str) -> Option<Flags> {
+            all().iter().find(|&&(n, _)| n == name).map(|&(_, flag)| flag)
+        }
+
+        #[allow(dead_code)]
+        pub fn name_of(flag: Flags) -> Option<&'static str> {
+            all().iter().find(|&&(_, f)| f == flag).map(|&(name, _)| name)
+        }
+
+        #[allow(dead_code)]
+        pub fn enabled_names() -> Vec<&'static str> {
+            let current = flags();
+            all().iter()
+                .filter(|&&(_, flag)| current.contains(flag))
+                .map(|&(name, _)| name)
+                .collect()
+        }
+
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct UnknownFeature(pub String);
+
+        impl ::std::fmt::Display for UnknownFeature {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                write!(f, "unknown feature: `{}`", self.0)
+            }
+        }
+
+        impl ::std::error::Error for UnknownFeature {
+            fn description(&self) -> &str {
+                "unknown feature"
+            }
+        }
+
+        #[allow(dead_code)]
+        pub fn parse(input: &str) -> Result<Flags, UnknownFeature> {
+            let mut flags = Flags::empty();
+            for token in input.split(|c: char| c == ',' || c.is_whitespace()) {
+                if token.is_empty() {
+                    continue;
+                }
+                match from_name(token) {
+                    Some(flag) => flags.insert(flag),
+                    None => return Err(UnknownFeature(token.to_string())),
+                }
+            }
+            Ok(flags)
+        }
+
+        #[allow(dead_code)]
+        pub fn enable_from_str(input: &str) -> Result<(), UnknownFeature> {
+            let flags = parse(input)?;
+            for &(_, flag) in all() {
+                if flags.contains(flag) {
+                    // A flag whose stability gate is closed stays disabled and the load goes
+                    // on; its `FeatureState` error is discarded, so callers that need the
+                    // error call `enable` directly.
+                    let _ = enable(flag);
+                }
+            }
+            Ok(())
+        }
+
+        #[allow(dead_code)]
+        pub fn load(input: &str) -> Result<(), UnknownFeature> {
+            enable_from_str(input)
+        }
+
+        #[allow(dead_code)]
+        pub fn enable_from_env(var: &str) -> Result<(), UnknownFeature> {
+            match ::std::env::var(var) {
+                Ok(value) => enable_from_str(&value),
+                Err(_) => Ok(()),
             }
         }
 
-        static mut FEATURES: atomic::AtomicUsize = atomic::ATOMIC_USIZE_INIT;
+        #[allow(dead_code)]
+        pub fn dump() -> String {
+            enabled_names().join(",")
+        }
+
+        fn warn_deprecated(flag: Flags, since: &str) {
+            let prev = DEPRECATION_WARNED.fetch_or(flag.bits(), atomic::Ordering::SeqCst);
+            if Flags::from_bits_truncate(prev) & flag != flag {
+                eprintln!(
+                    "warning: feature `{}` is deprecated since {}",
+                    name_of(flag).unwrap_or("?"),
+                    since
+                );
+            }
+        }
 
         #[allow(dead_code)]
-        pub fn enable(flag: Flags) {
-            let mut features = unsafe { FEATURES.get_mut() };
-            let mut flags = Flags::from_bits_truncate(*features);
-            flags.insert(flag);
-            *features = flags.bits();
+        pub fn enable(flag: Flags) -> Result<(), FeatureState> {
+            let closure = requirements_closure(flag);
+            // Gate every flag the closure pulls in, not just the one requested, so an
+            // unstable prerequisite can't be enabled behind a stable composite flag.
+            for &(_, f) in NAMES {
+                if !closure.contains(f) {
+                    continue;
+                }
+                match state_of(f) {
+                    Stability::Unstable { .. } if !unstable_allowed() => {
+                        return Err(FeatureState { flag: f, stability: state_of(f) });
+                    }
+                    Stability::Deprecated { since } => warn_deprecated(f, since),
+                    _ => {}
+                }
+            }
+            set(closure);
+            Ok(())
+        }
+
+        fn set(flag: Flags) {
+            FEATURES.fetch_or(flag.bits(), atomic::Ordering::SeqCst);
         }
 
         #[allow(dead_code)]
         pub fn disable(flag: Flags) {
-            let mut features = unsafe { FEATURES.get_mut() };
-            let mut flags = Flags::from_bits_truncate(*features);
-            flags.remove(flag);
-            *features = flags.bits();
+            let mut target = flag;
+            if DISABLE_DEPENDENTS.load(atomic::Ordering::SeqCst) {
+                loop {
+                    let mut next = target;
+                    for &(f, reqs) in REQUIRES {
+                        for &req in reqs {
+                            if target.contains(req) {
+                                next.insert(f);
+                            }
+                        }
+                    }
+                    if next == target {
+                        break;
+                    }
+                    target = next;
+                }
+            }
+            FEATURES.fetch_and(!target.bits(), atomic::Ordering::SeqCst);
         }
 
         #[allow(dead_code)]
@@ -236,9 +489,21 @@ macro_rules! features {
 
         #[allow(dead_code)]
         pub fn flags() -> Flags {
-            unsafe { Flags::from_bits_truncate(FEATURES.load(atomic::Ordering::Relaxed)) }
+            Flags::from_bits_truncate(FEATURES.load(atomic::Ordering::Relaxed))
         }
     };
+    (@_stability #[unstable(since = $since:tt)] $($rest:tt)*) => {
+        Stability::Unstable { since: $since }
+    };
+    (@_stability #[deprecated(since = $since:tt)] $($rest:tt)*) => {
+        Stability::Deprecated { since: $since }
+    };
+    (@_stability #$other:tt $($rest:tt)*) => {
+        features!(@_stability $($rest)*)
+    };
+    (@_stability) => {
+        Stability::Stable
+    };
 }
 
 #[cfg(test)]
@@ -255,16 +520,16 @@ mod tests {
         assert_eq!(false, f::is_enabled(f::Alpha));
         assert_eq!(false, f::is_enabled(f::Beta));
 
-        f::enable(f::Alpha);
+        f::enable(f::Alpha).unwrap();
         assert_eq!(true, f::is_enabled(f::Alpha));
         assert_eq!(false, f::is_enabled(f::Beta));
 
         // Enable again
-        f::enable(f::Alpha);
+        f::enable(f::Alpha).unwrap();
         assert_eq!(true, f::is_enabled(f::Alpha));
         assert_eq!(false, f::is_enabled(f::Beta));
 
-        f::enable(f::Beta);
+        f::enable(f::Beta).unwrap();
         assert_eq!(true, f::is_enabled(f::Alpha));
         assert_eq!(true, f::is_enabled(f::Beta));
     }
@@ -278,8 +543,8 @@ mod tests {
             }
         }
 
-        f::enable(f::Cool);
-        f::enable(f::Beans);
+        f::enable(f::Cool).unwrap();
+        f::enable(f::Beans).unwrap();
         assert_eq!(true, f::is_enabled(f::Cool));
         assert_eq!(true, f::is_enabled(f::Beans));
 
@@ -296,4 +561,129 @@ mod tests {
         assert_eq!(false, f::is_enabled(f::Cool));
         assert_eq!(false, f::is_enabled(f::Beans));
     }
+
+    #[test]
+    fn listing() {
+        features! {
+            pub mod f {
+                const Alpha = 0b00000001,
+                const Beta = 0b00000010
+            }
+        }
+
+        assert_eq!(2, f::all().len());
+        assert_eq!(Some(f::Alpha), f::from_name("Alpha"));
+        assert_eq!(None, f::from_name("Nope"));
+        assert_eq!(Some("Beta"), f::name_of(f::Beta));
+
+        assert!(f::enabled_names().is_empty());
+        f::enable(f::Beta).unwrap();
+        assert_eq!(vec!["Beta"], f::enabled_names());
+    }
+
+    #[test]
+    fn parsing() {
+        features! {
+            pub mod f {
+                const Alpha = 0b00000001,
+                const Beta = 0b00000010
+            }
+        }
+
+        assert_eq!(Ok(f::Alpha | f::Beta), f::parse("Alpha, Beta"));
+        assert_eq!(Ok(f::Alpha), f::parse("Alpha"));
+        assert_eq!(Ok(f::Flags::empty()), f::parse(""));
+        assert_eq!(Err(f::UnknownFeature("Nope".to_string())), f::parse("Nope"));
+
+        f::load("Alpha Beta").unwrap();
+        assert_eq!(true, f::is_enabled(f::Alpha));
+        assert_eq!(true, f::is_enabled(f::Beta));
+        assert_eq!("Alpha,Beta", f::dump());
+    }
+
+    #[test]
+    fn stability() {
+        features! {
+            pub mod f {
+                const Stable = 0b00000001,
+                #[unstable(since = "0.2")] const Beta = 0b00000010,
+                #[deprecated(since = "0.3")] const Old = 0b00000100
+            }
+        }
+
+        assert_eq!(f::Stability::Stable, f::state_of(f::Stable));
+        assert_eq!(f::Stability::Unstable { since: "0.2" }, f::state_of(f::Beta));
+
+        // Unstable flags are gated until explicitly allowed.
+        assert!(f::enable(f::Beta).is_err());
+        assert_eq!(false, f::is_enabled(f::Beta));
+
+        f::allow_unstable(true);
+        assert!(f::enable(f::Beta).is_ok());
+        assert_eq!(true, f::is_enabled(f::Beta));
+
+        // Deprecated flags still enable.
+        assert!(f::enable(f::Old).is_ok());
+        assert_eq!(true, f::is_enabled(f::Old));
+    }
+
+    #[test]
+    fn multiple_attributes() {
+        features! {
+            pub mod f {
+                const Stable = 0b00000001,
+                #[deprecated(since = "0.3")]
+                #[doc = "kept around for one more release"]
+                const Old = 0b00000010
+            }
+        }
+
+        // A non-stability attribute alongside the stability one is consumed without
+        // disturbing the classification.
+        assert_eq!(f::Stability::Deprecated { since: "0.3" }, f::state_of(f::Old));
+    }
+
+    #[test]
+    fn expression_values() {
+        features! {
+            pub mod f {
+                const Low = 1 << 0,
+                const High = 1 << 3
+            }
+        }
+
+        f::enable(f::High).unwrap();
+        assert_eq!(false, f::is_enabled(f::Low));
+        assert_eq!(true, f::is_enabled(f::High));
+    }
+
+    #[test]
+    fn implications() {
+        features! {
+            pub mod f {
+                const Basic = 0b00000001,
+                const Logging = 0b00000010,
+                const Advanced = 0b00000100 requires [Basic, Logging]
+            }
+        }
+
+        assert_eq!(f::Basic | f::Logging, f::requirements_of(f::Advanced));
+
+        // Enabling a flag pulls in its prerequisites transitively.
+        f::enable(f::Advanced).unwrap();
+        assert_eq!(true, f::is_enabled(f::Advanced));
+        assert_eq!(true, f::is_enabled(f::Basic));
+        assert_eq!(true, f::is_enabled(f::Logging));
+
+        // Disabling a prerequisite only cascades when the switch is on.
+        f::disable(f::Basic);
+        assert_eq!(false, f::is_enabled(f::Basic));
+        assert_eq!(true, f::is_enabled(f::Advanced));
+
+        f::enable(f::Advanced).unwrap();
+        f::disable_dependents(true);
+        f::disable(f::Basic);
+        assert_eq!(false, f::is_enabled(f::Basic));
+        assert_eq!(false, f::is_enabled(f::Advanced));
+    }
 }